@@ -0,0 +1,21 @@
+// a crate re-exporting the derive and its traits under its own name, e.g. a config crate's `builder`
+mod facade {
+    pub use builder_runtime::*;
+}
+
+#[allow(dead_code)]
+#[derive(Debug, facade::Builder)]
+#[builder(crate = "crate::facade")]
+pub struct Server {
+    #[builder(default = "localhost".into())]
+    host: String,
+    #[builder(default = 8080)]
+    port: u16,
+}
+
+fn main() {
+    let builder = <Server as facade::Buildable>::builder().port(3000u16);
+    let server = facade::Builder::build(builder);
+
+    println!("{:#?}", server);
+}
@@ -0,0 +1,27 @@
+//! Traits implemented by `#[derive(Builder)]` with `#[builder(runtime)]`, so generic code can build
+//! any such struct
+//!
+//! The derive is re-exported here too, so a crate only needs this one. A facade crate re-exporting it in
+//! turn names itself with `#[builder(crate = "...")]`, e.g. `#[builder(crate = "my_config::builder")]`
+
+/// The derive, which shares its name with the `Builder` trait like serde's `Serialize` does
+pub use builder::Builder;
+
+/// A struct with a builder, e.g. `Command` with its `CommandBuilder`
+pub trait Buildable {
+    type Builder;
+
+    /// The empty builder, same as the struct's own constructor, e.g. `Command::builder()`
+    fn builder() -> Self::Builder;
+}
+
+/// A builder, e.g. `CommandBuilder`
+pub trait Builder {
+    /// What the builder builds, e.g. `Command`
+    type Output;
+    /// Why it could not be built, e.g. `CommandBuilderError`
+    type Error;
+
+    /// Build the output, same as the builder's own build method, e.g. `finish`
+    fn build(self) -> Result<Self::Output, Self::Error>;
+}
@@ -0,0 +1,42 @@
+use std::fmt::Debug;
+
+use builder::Builder;
+use builder_runtime::Buildable;
+
+#[allow(dead_code)]
+#[derive(Debug, Builder)]
+#[builder(runtime)]
+pub struct Server {
+    #[builder(default = "localhost".into())]
+    host: String,
+    #[builder(default = 8080)]
+    port: u16,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Builder)]
+#[builder(runtime)]
+pub struct Database {
+    #[builder(default = "postgres://localhost".into())]
+    url: String,
+    pool_size: Option<u32>,
+}
+
+// written once for every config section
+fn load<T>() -> T
+where
+    T: Buildable,
+    T::Builder: builder_runtime::Builder<Output = T>,
+    <T::Builder as builder_runtime::Builder>::Error: Debug,
+{
+    let builder = T::builder();
+    builder_runtime::Builder::build(builder).expect("config has defaults")
+}
+
+fn main() {
+    let server: Server = load();
+    let database: Database = load();
+
+    println!("{:#?}", server);
+    println!("{:#?}", database);
+}
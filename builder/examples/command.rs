@@ -3,14 +3,22 @@ use builder::Builder;
 #[allow(dead_code)]
 #[derive(Debug, Builder)]
 pub struct Command {
+    #[builder(validate = "not_empty")]
     executable: String,
-    #[builder(each = "arg", default = "Default::default()")]
+    #[builder(each = "arg", default)]
     args: Vec<String>,
-    #[builder(each = "env", default = "vec![\"RUST_LOG=info\".into()]")]
-    env: Vec<String>,
+    #[builder(each = "env", default = vec!["RUST_LOG=info".into()])]
+    envs: Vec<String>,
     current_dir: Option<String>,
 }
 
+fn not_empty(executable: &str) -> Result<(), &'static str> {
+    if executable.is_empty() {
+        return Err("must not be empty");
+    }
+    Ok(())
+}
+
 fn main() {
     let command = Command::builder()
         .executable("find")
@@ -21,4 +29,12 @@ fn main() {
         .finish();
 
     println!("{:#?}", command);
+
+    // the same command in another directory
+    if let Ok(command) = &command {
+        println!("{:#?}", command.to_builder().current_dir("/tmp").finish());
+    }
+
+    let err = Command::builder().executable("").finish().unwrap_err();
+    println!("{}", err);
 }
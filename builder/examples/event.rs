@@ -0,0 +1,28 @@
+use builder::Builder;
+
+#[allow(dead_code)]
+#[derive(Debug, Builder)]
+pub enum Event {
+    Click {
+        x: i32,
+        y: i32,
+        button: Option<String>,
+    },
+    KeyPress {
+        key: char,
+        #[builder(each = "modifier", default = "Default::default()")]
+        modifiers: Vec<String>,
+    },
+    Close,
+}
+
+fn main() {
+    let click = Event::click_builder().x(10).y(20).button("left").finish();
+    let key_press = Event::key_press_builder()
+        .key('c')
+        .modifier("ctrl")
+        .finish();
+
+    println!("{:#?}", click);
+    println!("{:#?}", key_press);
+}
@@ -0,0 +1,26 @@
+use std::fmt::Debug;
+
+use builder::Builder;
+
+#[allow(dead_code)]
+#[derive(Debug, Builder)]
+pub struct Request<'a, T: Debug + ?Sized, const N: usize>
+where
+    T: PartialEq,
+{
+    body: &'a T,
+    #[builder(each = "header", default = "Default::default()")]
+    headers: Vec<String>,
+    retries: Option<[u8; N]>,
+}
+
+fn main() {
+    let body = String::from("{\"hello\": \"world\"}");
+    let request = Request::<str, 2>::builder()
+        .body(body.as_str())
+        .header("Content-Type: application/json")
+        .retries([1, 3])
+        .finish();
+
+    println!("{:#?}", request);
+}
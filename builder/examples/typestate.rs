@@ -0,0 +1,23 @@
+use builder::Builder;
+
+#[allow(dead_code)]
+#[derive(Debug, Builder)]
+#[builder(typestate)]
+pub struct Command {
+    executable: String,
+    #[builder(each = "arg", default = "Default::default()")]
+    args: Vec<String>,
+    current_dir: Option<String>,
+}
+
+fn main() {
+    // finish only exists once `executable` is set, and `executable` can only be set once
+    let command = Command::builder()
+        .arg("-name")
+        .arg("*.rs")
+        .current_dir("/user/davirain")
+        .executable("find")
+        .finish();
+
+    println!("{:#?}", command);
+}
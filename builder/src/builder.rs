@@ -1,191 +1,1936 @@
-use std::iter::Map;
-use std::slice::Iter;
-
+use crate::debug;
 use proc_macro2::Ident;
+use proc_macro2::Span;
 use proc_macro2::TokenStream;
+use proc_macro2::TokenTree;
+use quote::format_ident;
 use quote::quote;
+use quote::ToTokens;
+use syn::ext::IdentExt;
+use syn::parse::Parse;
+use syn::parse::ParseStream;
+use syn::parse_quote;
+use syn::parse_quote_spanned;
+use syn::spanned::Spanned;
+use syn::token;
+use syn::Attribute;
+use syn::Expr;
+use syn::ExprLit;
+use syn::Field;
 use syn::GenericArgument;
+use syn::GenericParam;
+use syn::Generics;
+use syn::Index;
+use syn::Lit;
+use syn::LitStr;
+use syn::Member;
 use syn::Path;
 use syn::PathArguments;
 use syn::Type;
+use syn::TypeParamBound;
 use syn::TypePath;
+use syn::TypeTraitObject;
+use syn::Visibility;
+use syn::Token;
+use darling::FromDeriveInput;
+use darling::util::Flag;
+use darling::util::Ignored;
+use darling::util::SpannedValue;
 use darling::FromField;
+use darling::FromMeta;
+use darling::FromVariant;
+use syn::punctuated::Punctuated;
+use syn::token::Comma;
 use syn::{
-    Data, DataStruct, DeriveInput, Fields, FieldsNamed,
+    Data, DataEnum, DataStruct, DeriveInput, Fields, FieldsNamed, FieldsUnnamed,
 };
 
-type TokenStreamIter<'a> = Map<Iter<'a, Fd>, fn(&'a Fd) -> TokenStream>;
+/// Options on the struct itself, e.g. #[builder(typestate)]
+#[derive(Debug, Default, FromDeriveInput)]
+#[darling(default, attributes(builder))]
+struct ContainerOpts {
+    typestate: bool,
+    // builder and constructor names of a struct, e.g. #[builder(name = "CmdSpec", constructor = "spec")]
+    name: Option<LitStr>,
+    constructor: Option<LitStr>,
+    // e.g. #[builder(build_fn = "build")]
+    build_fn: Option<LitStr>,
+    pattern: SpannedValue<Pattern>,
+    // #[builder(default)]: finish starts from the struct's Default, so no field is required
+    default: Flag,
+    // called with the built struct before finish returns it, e.g. #[builder(validate = "Command::check")]
+    validate: Option<LitStr>,
+    // for every field that doesn't set its own, e.g. #[builder(setter(into = false))]
+    setter: SetterOpts,
+    // write the generated code to target/builder/, see debug::write_expansion
+    debug: Flag,
+    // #[builder(runtime)]: implement the Buildable and Builder traits of builder_runtime, which the
+    // struct's crate then has to depend on
+    runtime: Flag,
+    // where the Buildable and Builder traits are, for a derive re-exported through a facade crate,
+    // e.g. #[builder(crate = "facade::builder")], implies `runtime`
+    #[darling(rename = "crate")]
+    krate: Option<LitStr>,
+    // `vis` itself would be filled with the struct's visibility by darling
+    #[darling(rename = "vis")]
+    builder_vis: Option<LitStr>,
+}
+
+/// How setters and the build method take the builder, e.g. #[builder(pattern = "mutable")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, FromMeta)]
+enum Pattern {
+    /// fn executable(mut self, ..) -> Self, fn finish(mut self)
+    #[default]
+    Owned,
+    /// fn executable(&mut self, ..) -> &mut Self, fn finish(&self)
+    Mutable,
+    /// fn executable(&self, ..) -> Self, fn finish(&self)
+    Immutable,
+}
+
+impl Pattern {
+    // what setter bodies modify: the builder itself, or a clone of it for the immutable pattern
+    fn this(self) -> TokenStream {
+        match self {
+            Pattern::Owned | Pattern::Mutable => quote! { self },
+            Pattern::Immutable => quote! { builder },
+        }
+    }
+
+    // e.g. fn executable(mut self, v: impl Into<String>) -> Self { self.executable = Some(v.into()); self }
+    fn setter(self, vis: &Visibility, name: &Ident, params: TokenStream, body: TokenStream) -> TokenStream {
+        match self {
+            Pattern::Owned => quote! {
+                #vis fn #name(mut self, #params) -> Self {
+                    #body
+                    self
+                }
+            },
+            Pattern::Mutable => quote! {
+                #vis fn #name(&mut self, #params) -> &mut Self {
+                    #body
+                    self
+                }
+            },
+            Pattern::Immutable => quote! {
+                #vis fn #name(&self, #params) -> Self {
+                    let mut builder = ::core::clone::Clone::clone(self);
+                    #body
+                    builder
+                }
+            },
+        }
+    }
+
+    // e.g. fn try_port<__V: TryInto<u16>>(mut self, v: __V) -> Result<Self, __V::Error>
+    fn try_setter(self, vis: &Visibility, name: &Ident, ty: &Type, body: TokenStream) -> TokenStream {
+        let error = quote! { <__V as ::core::convert::TryInto<#ty>>::Error };
+        match self {
+            Pattern::Owned => quote! {
+                #vis fn #name<__V: ::core::convert::TryInto<#ty>>(mut self, v: __V) -> ::core::result::Result<Self, #error> {
+                    #body
+                    ::core::result::Result::Ok(self)
+                }
+            },
+            Pattern::Mutable => quote! {
+                #vis fn #name<__V: ::core::convert::TryInto<#ty>>(&mut self, v: __V) -> ::core::result::Result<&mut Self, #error> {
+                    #body
+                    ::core::result::Result::Ok(self)
+                }
+            },
+            Pattern::Immutable => quote! {
+                #vis fn #name<__V: ::core::convert::TryInto<#ty>>(&self, v: __V) -> ::core::result::Result<Self, #error> {
+                    let mut builder = ::core::clone::Clone::clone(self);
+                    #body
+                    ::core::result::Result::Ok(builder)
+                }
+            },
+        }
+    }
+
+    // the build method consumes an owned builder, the others can build any number of times
+    fn build_receiver(self) -> TokenStream {
+        match self {
+            Pattern::Owned => quote! { mut self },
+            Pattern::Mutable | Pattern::Immutable => quote! { &self },
+        }
+    }
+
+    // how the build method gets a value out of a slot, e.g. self.executable.take()
+    fn build_value(self, name: &Ident) -> TokenStream {
+        match self {
+            Pattern::Owned => quote! { self.#name.take() },
+            Pattern::Mutable | Pattern::Immutable => quote! { ::core::clone::Clone::clone(&self.#name) },
+        }
+    }
+}
+
+/// Options on an enum variant, e.g. #[builder(name = "ClickSpec", constructor = "click")]
+#[derive(Debug, Default, FromVariant)]
+#[darling(default, attributes(builder))]
+struct VariantOpts {
+    name: Option<LitStr>,
+    constructor: Option<LitStr>,
+}
 
+/// How setters take their value, e.g. #[builder(setter(into = false))]
+#[derive(Debug, Default, Clone, Copy, FromMeta)]
+#[darling(default)]
+struct SetterOpts {
+    // take impl Into<T> instead of T, on by default
+    into: Option<bool>,
+}
+
+/// Options on a single field, e.g. #[builder(each = "arg")]
 #[derive(Debug, Default, FromField)]
 #[darling(default, attributes(builder))]
 struct Opts {
-    each: Option<String>,
-    default: Option<String>,
+    name: Option<LitStr>,
+    each: Option<LitStr>,
+    // `default` itself is taken out of the attribute before darling sees it, see take_default,
+    // it's only listed so a typo like `defualt` is pointed to it
+    default: Option<Ignored>,
+    default_fn: Option<LitStr>,
+    kind: Option<SpannedValue<Kind>>,
+    // e.g. #[builder(validate = "not_empty")] with fn not_empty(v: &String) -> Result<(), E>
+    validate: Option<LitStr>,
+    // also generate a fallible try_ setter taking impl TryInto<T>
+    try_setter: Flag,
+    // no slot or setter, finish fills in the default, e.g. #[builder(skip, default = Cache::new())]
+    skip: Flag,
+    setter: Option<SpannedValue<SetterOpts>>,
+    // #[builder(strip_option = false)]: the setter of an Option field takes the Option itself
+    strip_option: Option<SpannedValue<bool>>,
+}
+
+/// What a field's type is, for types that can't be recognised from their name,
+/// e.g. #[builder(kind = "vec")] on args: Args where type Args = Vec<String>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromMeta)]
+enum Kind {
+    Option,
+    /// any collection of single items, e.g. a Vec, VecDeque or HashSet
+    Vec,
+    /// any collection of key-value pairs, e.g. an IndexMap or an alias of a HashMap
+    Map,
+    Plain,
+}
+
+/// What the `each` setter of a collection field adds, the collection itself only needs Default + Extend
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)] // one per field, boxing buys nothing
+enum Collection {
+    // e.g. Vec<String>, VecDeque<String>, BTreeSet<String> -> String
+    Items(Type),
+    // e.g. HashMap<String, u8> -> (String, u8)
+    Map(Type, Type),
+    // any other map, e.g. env: Env with #[builder(kind = "map")] -> <Env as IntoIterator>::Item, which is
+    // taken apart into key and value by the builder's entry trait, see BuilderContext::gen_entry_trait
+    Entries(Type),
 }
 
 #[derive(Debug)]
 struct Fd {
+    // name of the builder slot and setter, e.g. executable, or field0 for the first tuple field
     name: Ident,
+    // the field in the target struct, e.g. executable, or 0
+    member: Member,
     ty: Type,
-    opts: Opts,
+    // inner type of an optional field, e.g. current_dir: Option<String> -> String
+    option_inner: Option<Type>,
+    // what a collection field is made of, e.g. args: Vec<String> -> Items(String)
+    collection: Option<Collection>,
+    // name of the single-item setter, e.g. #[builder(each = "arg")] -> arg
+    each: Option<Ident>,
+    // the field's value when it was never set, of the field's own type, e.g. #[builder(default = vec![])],
+    // or #[builder(default = Some(8080))] for port: Option<u16>
+    default: Option<Expr>,
+    // the struct has #[builder(default)], an unset field keeps the value of the struct's Default
+    struct_default: bool,
+    // checks the field's final value in finish
+    validate: Option<Path>,
+    // e.g. try_port next to port
+    try_setter: bool,
+    // never set by callers, see BuilderContext::skipped
+    skip: bool,
+    // #[builder(setter(into = ..))] of the field, or else of the struct
+    into: Option<bool>,
+    // an Option field with strip_option = false, its setter takes the whole Option and it's None when unset
+    optional: bool,
 }
 
+impl Fd {
+    fn new(index: usize, mut f: Field) -> darling::Result<Self> {
+        let mut errors = darling::Error::accumulator();
+        let default = errors.handle(take_default(&mut f.attrs)).flatten();
+        // a malformed attribute is reported, the field is still checked with the defaults
+        let opts = errors.handle(Opts::from_field(&f)).unwrap_or_default();
+
+        let member = match &f.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(index)),
+        };
+        // positional fields are called field0, field1, .. unless they are given a name
+        let name = match &opts.name {
+            Some(name) => errors.handle(parse_lit_str(name, "`name`").map_err(Into::into)),
+            None => Some(f.ident.clone().unwrap_or_else(|| format_ident!("field{}", index))),
+        };
+
+        // a field declared to be an Option or a collection with `kind` may be an alias, Option<T>
+        // and collections both iterate over what they are made of, e.g. <Args as IntoIterator>::Item
+        let ty = &f.ty;
+        let option_inner = match opts.kind.as_deref() {
+            None => errors.handle(get_option_inner(&f.ty).map_err(Into::into)).flatten().cloned(),
+            Some(Kind::Option) => match get_option_inner(&f.ty) {
+                Ok(Some(inner)) => Some(inner.clone()),
+                _ => Some(parse_quote!(<#ty as ::core::iter::IntoIterator>::Item)),
+            },
+            Some(Kind::Vec | Kind::Map | Kind::Plain) => None,
+        };
+        let collection = match opts.kind.as_deref() {
+            None => errors.handle(get_collection(&f.ty).map_err(Into::into)).flatten(),
+            Some(Kind::Vec) => match get_collection(&f.ty) {
+                Ok(Some(collection)) => Some(collection),
+                _ => Some(Collection::Items(parse_quote!(<#ty as ::core::iter::IntoIterator>::Item))),
+            },
+            Some(Kind::Map) => match get_collection(&f.ty) {
+                Ok(Some(collection @ Collection::Map(..))) => Some(collection),
+                _ => Some(Collection::Entries(parse_quote!(<#ty as ::core::iter::IntoIterator>::Item))),
+            },
+            Some(Kind::Option | Kind::Plain) => None,
+        };
+        let each = opts
+            .each
+            .and_then(|each| errors.handle(parse_lit_str(&each, "`each`").map_err(Into::into)));
+        // e.g. #[builder(default_fn = "Args::new")] -> Args::new()
+        let default_fn = opts.default_fn.and_then(|lit| {
+            if default.is_some() {
+                errors.push(darling::Error::custom("use either `default` or `default_fn`").with_span(&lit));
+            }
+            let path = errors.handle(parse_lit_str::<Path>(&lit, "`default_fn`").map_err(Into::into))?;
+            // spanned, so a function returning the wrong type is reported at the attribute
+            Some(parse_quote_spanned!(lit.span()=> #path()))
+        });
+        let default = default.or(default_fn);
+        // without strip_option the setter takes the field's own type, see Fd::optional
+        let optional = match opts.strip_option {
+            Some(strip) if option_inner.is_none() => {
+                errors.push(
+                    darling::Error::custom("`strip_option` only applies to Option fields").with_span(&strip),
+                );
+                false
+            }
+            Some(strip) => !*strip,
+            None => false,
+        };
+        let option_inner = if optional { None } else { option_inner };
+        let validate = opts.validate.and_then(|lit| {
+            errors.handle(parse_lit_str::<Path>(&lit, "`validate`").map_err(Into::into))
+        });
+
+        // PhantomData only ever has the one value
+        let skip = opts.skip.is_present() || is_phantom_data(&f.ty);
+        if opts.skip.is_present() {
+            if let Some(each) = &each {
+                errors.push(darling::Error::custom("a skipped field has no `each` setter").with_span(each));
+            }
+            if opts.try_setter.is_present() {
+                errors.push(darling::Error::custom("a skipped field has no `try_setter`").with_span(&opts.try_setter));
+            }
+            if let Some(setter) = &opts.setter {
+                errors.push(darling::Error::custom("a skipped field has no setter to configure").with_span(setter));
+            }
+            if let Some(strip) = &opts.strip_option {
+                errors.push(darling::Error::custom("a skipped field has no setter to take an Option").with_span(strip));
+            }
+            if let Some(name) = &opts.name {
+                errors.push(darling::Error::custom("a skipped field has no setter to name").with_span(name));
+            }
+            if let Some(kind) = &opts.kind {
+                errors.push(
+                    darling::Error::custom("a skipped field has no setters that depend on its `kind`").with_span(kind),
+                );
+            }
+        }
+
+        // the whole-collection setter keeps the field's name, so `each` needs one of its own
+        if let (Some(name), Some(each)) = (&name, &each) {
+            if name == each {
+                errors.push(
+                    darling::Error::custom(format!(
+                        "`each` setter has the same name as the setter of `{}`, pick a different one",
+                        field_name_str(name),
+                    ))
+                    .with_span(each),
+                );
+            }
+        }
+
+        errors.finish()?;
+        Ok(Self {
+            // only missing if parsing it failed, which finish already reported
+            name: name.expect("field name"),
+            member,
+            option_inner,
+            collection,
+            ty: f.ty,
+            each,
+            default,
+            struct_default: false,
+            validate,
+            try_setter: opts.try_setter.is_present(),
+            skip,
+            into: opts.setter.as_ref().and_then(|setter| setter.into),
+            optional,
+        })
+    }
+
+    // the type the setter takes, e.g. executable: String -> String, current_dir: Option<String> -> String
+    fn setter_ty(&self) -> &Type {
+        self.option_inner.as_ref().unwrap_or(&self.ty)
+    }
+
+    // the setter's parameter type and how its `v` becomes the slot's value,
+    // e.g. (impl Into<String>, Into::into(v)), or (impl Fn(u8) + 'static, Box::new(v)) for a Box<dyn Fn(u8)>
+    fn setter_arg(&self) -> (TokenStream, TokenStream) {
+        let ty = self.setter_ty();
+        match (self.into, get_boxed_dyn(ty)) {
+            (None, Some(bounds)) => {
+                // the box must live as long as the trait object says, 'static when it says nothing
+                let lifetime = if bounds.iter().any(|b| matches!(b, TypeParamBound::Lifetime(_))) {
+                    quote! {}
+                } else {
+                    quote! { + 'static }
+                };
+                (quote! { impl #bounds #lifetime }, quote! { ::std::boxed::Box::new(v) })
+            }
+            (into, _) => into_arg(into.unwrap_or(true), ty, quote! { v }),
+        }
+    }
+
+    // the type the slot holds once the field is set, which is what the setter takes, except for an Option
+    // field with a default, whose slot holds the whole Option, see Fd::slot_value
+    fn slot_ty(&self) -> &Type {
+        if self.defaulted_option() {
+            &self.ty
+        } else {
+            self.setter_ty()
+        }
+    }
+
+    // e.g. port: Option<u16> with #[builder(default = Some(8080))], or any Option field of a struct with
+    // #[builder(default)], where clearing it replaces the struct's Default with None
+    fn defaulted_option(&self) -> bool {
+        self.option_inner.is_some() && (self.default.is_some() || self.struct_default)
+    }
+
+    // what the setters store in the slot for `option`, e.g. Some(v), or None to clear an Option field.
+    // a defaulted Option field wraps it once more, so a cleared field, Some(None), keeps None in finish
+    // while an unset one, None, gets the default
+    fn slot_value(&self, option: TokenStream) -> TokenStream {
+        if self.defaulted_option() {
+            quote! { ::core::option::Option::Some(#option) }
+        } else {
+            option
+        }
+    }
+
+    // the value of a field with a default from its slot, e.g. self.args.take().unwrap_or_else(|| vec![])
+    fn defaulted_value(&self, value: TokenStream, default: &Expr) -> TokenStream {
+        quote! { #value.unwrap_or_else(|| #default) }
+    }
+
+    // a field that has to be set before finish, i.e. neither an Option nor defaulted
+    fn is_required(&self) -> bool {
+        self.option_inner.is_none() && !self.optional && self.default.is_none() && !self.struct_default
+    }
+}
 
 #[derive(Debug)]
 pub struct BuilderContext {
     name: Ident,
+    generics: Generics,
+    // visibility of the builder, its methods and the constructor
+    vis: Visibility,
+    // the enum variant this builder builds, e.g. Click for Event::Click
+    variant: Option<Ident>,
+    // e.g. CommandBuilder
+    builder_name: Ident,
+    // the function on the struct returning the builder, e.g. builder
+    constructor_name: Ident,
+    // the builder method returning the struct, e.g. finish
+    build_fn: Ident,
     fields: Vec<Fd>,
+    // fields without a slot or setter, finish initialises them from their default
+    skipped: Vec<Fd>,
+    // track which required fields are set in the builder's type, see generate_typestate
+    typestate: bool,
+    pattern: Pattern,
+    // finish starts from the struct's Default, see gen_struct_default
+    struct_default: bool,
+    // checks the whole struct in finish, after the fields' own validators
+    validate: Option<Path>,
+    // the crate with the Buildable and Builder traits, e.g. ::builder_runtime, None without #[builder(runtime)]
+    runtime: Option<Path>,
 }
 
-impl BuilderContext {
-    pub fn new(input: DeriveInput) -> Self {
-        let name = input.ident;
-        let fields = if let Data::Struct(DataStruct {
-            fields: Fields::Named(FieldsNamed { named, .. }),
-            ..
-        }) = input.data
-        {
-            named
+/// Parse the derive input into one BuilderContext per builder: one for a struct,
+/// or one for every variant with fields of an enum
+pub fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let opts = ContainerOpts::from_derive_input(&input);
+
+    let name = input.ident;
+    let generics = input.generics;
+    let vis = input.vis;
+    let is_enum = matches!(input.data, Data::Enum(_));
+    // #[builder(..)] on unit variants, which would do nothing
+    let mut unit_attrs = Vec::new();
+    // (variant, variant options, fields) of every builder, e.g. [(None, _, {executable, args})]
+    // or [(Some(Click), _, {x, y})]
+    let targets = match input.data {
+        Data::Struct(DataStruct { fields, .. }) => match fields_of(fields) {
+            Some(fields) => vec![(None, Ok(VariantOpts::default()), fields)],
+            None => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "Builder can not be derived for unit structs",
+                ))
+            }
+        },
+        Data::Enum(DataEnum { variants, .. }) => {
+            // unit variants don't get a builder
+            let targets: Vec<_> = variants
+                .into_iter()
+                .filter_map(|v| {
+                    let variant_opts = VariantOpts::from_variant(&v);
+                    match fields_of(v.fields) {
+                        Some(fields) => Some((Some(v.ident), variant_opts, fields)),
+                        None => {
+                            unit_attrs.extend(v.attrs.into_iter().filter(|attr| attr.path.is_ident("builder")));
+                            None
+                        }
+                    }
+                })
+                .collect();
+            if targets.is_empty() {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "Builder needs at least one enum variant with fields",
+                ));
+            }
+            targets
+        }
+        Data::Union(u) => {
+            return Err(syn::Error::new_spanned(
+                u.union_token,
+                "Builder can not be derived for unions",
+            ))
+        }
+    };
+
+    // collect every error found in the input, so they are all reported in one go
+    let mut errors = darling::Error::accumulator();
+    let opts = errors.handle(opts).unwrap_or_default();
+    for attr in &unit_attrs {
+        errors.push(darling::Error::custom("a unit variant has no builder to configure").with_span(&attr.path));
+    }
+    // the builder is as visible as the struct, unless overridden, e.g. #[builder(vis = "pub(crate)")]
+    let struct_vis = vis;
+    let vis = match &opts.builder_vis {
+        Some(lit) => errors
+            .handle(parse_lit_str(lit, "`vis`").map_err(Into::into))
+            .unwrap_or_else(|| struct_vis.clone()),
+        None => struct_vis.clone(),
+    };
+    if is_enum && opts.default.is_present() {
+        // an enum's Default is one particular variant
+        errors.push(
+            darling::Error::custom("`default` is not supported for enums").with_span(&opts.default),
+        );
+    }
+    // parsed once here, an enum's builders all share them
+    let validate = opts.validate.as_ref().and_then(|lit| {
+        errors.handle(parse_lit_str::<Path>(lit, "`validate`").map_err(Into::into))
+    });
+    let runtime = match &opts.krate {
+        Some(lit) => errors.handle(parse_lit_str::<Path>(lit, "`crate`").map_err(Into::into)),
+        None if opts.runtime.is_present() => Some(parse_quote!(::builder_runtime)),
+        None => None,
+    };
+    // the trait impls show the builder as the struct's Buildable::Builder and the struct as the builder's
+    // Builder::Output, so neither may be less visible than the other, e.g. a pub(crate) builder of a pub struct
+    if let (Some(_), Some(lit)) = (&runtime, &opts.builder_vis) {
+        let reason = if !is_enum && is_narrower(&vis, &struct_vis) {
+            Some(format!("the builder is less visible than `{0}`, so it can't be `{0}`'s `Buildable::Builder`", name))
+        } else if is_narrower(&struct_vis, &vis) {
+            Some(format!("the builder is more visible than `{0}`, so `{0}` can't be its `Builder::Output`", name))
         } else {
-            panic!("Unsupported data type");
+            None
         };
+        if let Some(reason) = reason {
+            errors.push(darling::Error::custom(reason).with_span(lit));
+        }
+    }
+    if is_enum {
+        // an enum has a builder per variant, so they can't all share one name
+        for lit in opts.name.iter().chain(&opts.constructor) {
+            errors.push(
+                darling::Error::custom("set the builder names of an enum on its variants instead")
+                    .with_span(lit),
+            );
+        }
+    }
+    let contexts: Vec<BuilderContext> = targets
+        .into_iter()
+        .filter_map(|(variant, variant_opts, fields)| {
+            // a struct's builder is named on the struct itself
+            let names = match &variant {
+                Some(_) => errors.handle(variant_opts)?,
+                None => VariantOpts {
+                    name: opts.name.clone(),
+                    constructor: opts.constructor.clone(),
+                },
+            };
+            let ctx = errors.handle(BuilderContext::new(
+                &name, &generics, &vis, &opts, variant, names, fields,
+            ))?;
+            Some(BuilderContext {
+                validate: validate.clone(),
+                runtime: runtime.clone(),
+                ..ctx
+            })
+        })
+        .collect();
+    errors.finish()?;
 
-        let fds = fields.into_iter().map(|f| {
-            Fd {
-                opts: Opts::from_field(&f).unwrap_or_default(),
-                name: f.ident.unwrap(),
-                ty: f.ty,
+    let tokens = contexts.iter().map(BuilderContext::generate).collect();
+    if opts.debug.is_present() || debug::requested(&name) {
+        debug::write_expansion(&name, &tokens).map_err(|e| {
+            let span = if opts.debug.is_present() { opts.debug.span() } else { name.span() };
+            syn::Error::new(span, format!("could not write the expansion of `{}`: {}", name, e))
+        })?;
+    }
+    Ok(tokens)
+}
+
+// named or positional fields, None for a unit struct or variant
+fn fields_of(fields: Fields) -> Option<Punctuated<Field, Comma>> {
+    match fields {
+        Fields::Named(FieldsNamed { named, .. }) => Some(named),
+        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => Some(unnamed),
+        Fields::Unit => None,
+    }
+}
+
+impl BuilderContext {
+    fn new(
+        name: &Ident,
+        generics: &Generics,
+        vis: &Visibility,
+        opts: &ContainerOpts,
+        variant: Option<Ident>,
+        names: VariantOpts,
+        fields: Punctuated<Field, Comma>,
+    ) -> darling::Result<Self> {
+        let mut errors = darling::Error::accumulator();
+        let target = variant.as_ref().unwrap_or(name);
+        // builder name: {}Builder, e.g. CommandBuilder, or ClickBuilder for Event::Click
+        let builder_name = match &names.name {
+            Some(lit) => errors.handle(parse_lit_str(lit, "`name`").map_err(Into::into)),
+            None => None,
+        }
+        .unwrap_or_else(|| format_ident!("{}Builder", target, span = target.span()));
+        // constructor name: builder, or click_builder for Event::Click
+        let constructor_name = match &names.constructor {
+            Some(lit) => errors.handle(parse_lit_str(lit, "`constructor`").map_err(Into::into)),
+            None => None,
+        }
+        .unwrap_or_else(|| match &variant {
+            Some(variant) => format_ident!("{}_builder", snake_case(variant), span = variant.span()),
+            None => format_ident!("builder"),
+        });
+        let build_fn = match &opts.build_fn {
+            Some(lit) => errors.handle(parse_lit_str(lit, "`build_fn`").map_err(Into::into)),
+            None => None,
+        }
+        .unwrap_or_else(|| format_ident!("finish"));
+
+        let fds: Vec<Fd> = fields
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, f)| errors.handle(Fd::new(i, f)))
+            .map(|f| Fd {
+                struct_default: opts.default.is_present(),
+                into: f.into.or(opts.setter.into),
+                ..f
+            })
+            .collect();
+        let (skipped, fds): (Vec<Fd>, Vec<Fd>) = fds.into_iter().partition(|f| f.skip);
+
+        // options that would be silently ignored
+        for f in &fds {
+            if let (Some(each), None) = (&f.each, &f.collection) {
+                errors.push(
+                    darling::Error::custom(
+                        "`each` needs a collection field, e.g. Vec<T>, add `kind = \"vec\"` or `kind = \"map\"` if it is an alias of one",
+                    )
+                    .with_span(each),
+                );
+            }
+        }
+
+        if opts.typestate && *opts.pattern != Pattern::Owned {
+            // every setter of a required field changes the builder's type
+            errors.push(
+                darling::Error::custom("`typestate` builders only support the owned pattern")
+                    .with_span(&opts.pattern),
+            );
+        }
+
+        if opts.typestate {
+            // a required field's state goes from unset to set exactly once, an `each` setter
+            // would have to be callable in both states
+            for f in fds.iter().filter(|f| f.is_required()) {
+                if let Some(each) = &f.each {
+                    errors.push(
+                        darling::Error::custom(
+                            "`each` on a required field is not supported with `typestate`, add a `default`",
+                        )
+                        .with_span(each),
+                    );
+                }
+            }
+        }
+
+        errors.finish_with(Self {
+            name: name.clone(),
+            generics: generics.clone(),
+            vis: vis.clone(),
+            variant,
+            builder_name,
+            constructor_name,
+            build_fn,
+            fields: fds,
+            skipped,
+            typestate: opts.typestate,
+            pattern: *opts.pattern,
+            struct_default: opts.default.is_present(),
+            validate: None,
+            runtime: None,
+        })
+    }
+
+    // path of what finish builds, e.g. Command or Event::Click
+    fn target(&self) -> TokenStream {
+        let name = &self.name;
+        match &self.variant {
+            Some(variant) => quote! { #name::#variant },
+            None => quote! { #name },
+        }
+    }
+
+    // e.g. "Command" or "Event::Click"
+    fn target_str(&self) -> String {
+        match &self.variant {
+            Some(variant) => format!("{}::{}", self.name, variant),
+            None => self.name.to_string(),
+        }
+    }
+
+    // error name: {}BuilderError, e.g. CommandBuilderError
+    fn error_name(&self) -> Ident {
+        format_ident!("{}Error", self.builder_name)
+    }
+
+    // e.g. __ConfigBuilderEntry, see gen_entry_trait
+    fn entry_trait(&self) -> Ident {
+        format_ident!("__{}Entry", self.builder_name)
+    }
+
+    // the key and value types of a map the derive doesn't know, e.g. for env: Env with kind = "map",
+    // fn env(k: impl Into<<<Env as IntoIterator>::Item as __ConfigBuilderEntry>::Key>, ..)
+    fn gen_entry_trait(&self) -> TokenStream {
+        if !self.fields.iter().any(|f| matches!(f.collection, Some(Collection::Entries(_)))) {
+            return quote! {};
+        }
+
+        let vis = &self.vis;
+        let entry = self.entry_trait();
+        quote! {
+            #[doc(hidden)]
+            #vis trait #entry {
+                type Key;
+                type Value;
             }
-        }).collect();
-   
-        Self { name, fields: fds }
+
+            impl<__K, __V> #entry for (__K, __V) {
+                type Key = __K;
+                type Value = __V;
+            }
+        }
     }
 
     pub fn generate(&self) -> TokenStream {
+        if self.typestate {
+            return self.generate_typestate();
+        }
+
         let name = &self.name;
-        // builder name: {}Builder, e.g.CommandBuilder
-        let builder_name = Ident::new(&format!("{}Builder", name), name.span());
+        let vis = &self.vis;
+        let builder_name = &self.builder_name;
+        let constructor_name = &self.constructor_name;
+        let build_fn = &self.build_fn;
         // option filels. e.g. executable: String -> executable: Option<String>
         let optionized_fields = self.gen_optionized_fields();
         // method: fn executable(mut self, v: impl Into<String>) -> Self { self.executable = Some(v); self}
         // Command::Builder().executable("hello").args(vec![]).envs(vec![]).finish()
         let methods = self.gen_methods();
-        // assign build fileds back to origin struct fields
-        // field_name: self.#field_name.take().ok_or(" xx need to be set!")
-        let assigns = self.gen_assigns();
+        // finish: take out every required field, and report all of the missing ones at once
+        let finish = self.gen_finish();
+        // e.g. CommandBuilderError
+        let error_name = self.error_name();
+        let error = self.gen_error();
+        // every builder slot starts out empty, e.g. executable: None
+        // (spelled out instead of Default::default() so generic params don't need to be Default)
+        let empty_fields = self.fields.iter().map(|f| &f.name);
+
+        // the builder carries the struct's generics verbatim: params with their bounds, then the where-clause
+        let generics = &self.generics;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        // the non-owned patterns build from a shared reference, so they clone
+        let derive_clone = match self.pattern {
+            Pattern::Owned => quote! {},
+            Pattern::Mutable | Pattern::Immutable => quote! { #[derive(::core::clone::Clone)] },
+        };
+        let build_receiver = self.pattern.build_receiver();
+        let build_where = self.gen_build_where();
+        let debug = self.gen_debug(&self.generics, quote! { #builder_name #ty_generics }, &[]);
+        let build = match self.pattern {
+            Pattern::Owned => quote! { Self::#build_fn(self) },
+            Pattern::Mutable | Pattern::Immutable => quote! { Self::#build_fn(&self) },
+        };
+        let trait_impls = self.gen_trait_impls(
+            quote! { #builder_name #ty_generics },
+            quote! { #builder_name #ty_generics },
+            quote! { #error_name },
+            build,
+        );
+        let to_builder = self.gen_to_builder(quote! { #builder_name #ty_generics });
+        let entry_trait = self.gen_entry_trait();
 
         quote! {
             /// Builder structure
-            #[derive(Debug, Default)]
-            struct #builder_name {
+            #[derive(::core::default::Default)]
+            #derive_clone
+            #vis struct #builder_name #generics #where_clause {
                 #(#optionized_fields,)*
+                // an enum variant may not use all of the enum's params, keep them in use
+                __marker: ::core::marker::PhantomData<fn() -> #name #ty_generics>,
             }
 
-            impl #builder_name {
+            #debug
+
+            impl #impl_generics #builder_name #ty_generics #where_clause {
                 #(#methods)*
 
-                pub fn finish(mut self) -> Result<#name, &'static str> {
-                    Ok(#name {
-                        #(#assigns,)*
-                    })
+                #vis fn #build_fn(#build_receiver) -> ::core::result::Result<#name #ty_generics, #error_name>
+                #build_where
+                {
+                    #finish
                 }
 
             }
 
-            impl #name {
-                fn builder() -> #builder_name {
-                    Default::default()
+            #error
+
+            #trait_impls
+
+            #to_builder
+
+            #entry_trait
+
+            impl #impl_generics #name #ty_generics #where_clause {
+                #vis fn #constructor_name() -> #builder_name #ty_generics {
+                    #builder_name {
+                        #(#empty_fields: ::core::option::Option::None,)*
+                        __marker: ::core::marker::PhantomData,
+                    }
                 }
             }
         }
     }
 
-    fn gen_optionized_fields(&self) -> TokenStreamIter {
+    // e.g. CommandBuilderError { missing_fields: vec!["executable"], .. }, every validator's
+    // error is kept as its message so the error stays Clone + Eq
+    fn gen_error(&self) -> TokenStream {
+        let vis = &self.vis;
+        let error_name = self.error_name();
+        let target_str = self.target_str();
+
+        quote! {
+            /// Error returned by the builder's finish when required fields were not set or did not validate
+            #[derive(::core::fmt::Debug, ::core::clone::Clone, ::core::cmp::PartialEq, ::core::cmp::Eq)]
+            #vis struct #error_name {
+                missing_fields: ::std::vec::Vec<&'static str>,
+                invalid_fields: ::std::vec::Vec<(&'static str, ::std::string::String)>,
+                invalid_reason: ::core::option::Option<::std::string::String>,
+            }
+
+            impl #error_name {
+                /// Names of every required field that was not set
+                #vis fn missing_fields(&self) -> &[&'static str] {
+                    &self.missing_fields
+                }
+
+                /// Every field whose validator failed, with the validator's message
+                #vis fn invalid_fields(&self) -> &[(&'static str, ::std::string::String)] {
+                    &self.invalid_fields
+                }
+
+                /// Message of the struct's own validator, if that is what failed
+                #vis fn invalid_reason(&self) -> ::core::option::Option<&str> {
+                    ::core::option::Option::as_deref(&self.invalid_reason)
+                }
+            }
+
+            impl ::core::fmt::Display for #error_name {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    if !self.missing_fields.is_empty() {
+                        ::core::write!(f, "{} is missing required fields: {}", #target_str, self.missing_fields.join(", "))
+                    } else if let ::core::option::Option::Some(reason) = &self.invalid_reason {
+                        ::core::write!(f, "{} is invalid: {}", #target_str, reason)
+                    } else {
+                        ::core::write!(f, "{} has invalid fields: ", #target_str)?;
+                        for (i, (field, reason)) in ::core::iter::Iterator::enumerate(self.invalid_fields.iter()) {
+                            if i > 0 {
+                                f.write_str(", ")?;
+                            }
+                            ::core::write!(f, "{} ({})", field, reason)?;
+                        }
+                        ::core::result::Result::Ok(())
+                    }
+                }
+            }
+
+            impl ::std::error::Error for #error_name {}
+        }
+    }
+
+    fn has_validators(&self) -> bool {
+        self.validate.is_some() || self.fields.iter().chain(&self.skipped).any(|f| f.validate.is_some())
+    }
+
+    // runs the validators on the built value and returns early if any of them fails, e.g.
+    // match &__built { Command { executable: __executable, .. } => { if let Err(e) = not_empty(__executable) { .. } } }
+    // every field validator runs, so all the invalid fields are reported at once
+    fn gen_validate(&self) -> TokenStream {
+        let target = self.target();
+        let error_name = self.error_name();
+        let validated: Vec<&Fd> = self
+            .fields
+            .iter()
+            .chain(&self.skipped)
+            .filter(|f| f.validate.is_some())
+            .collect();
+        let members = validated.iter().map(|f| &f.member);
+        let bindings: Vec<Ident> = validated
+            .iter()
+            .map(|f| format_ident!("__{}", field_name_str(&f.name)))
+            .collect();
+        let paths = validated.iter().map(|f| &f.validate);
+        let names = validated.iter().map(|f| field_name_str(&f.name));
+        // mixed_site like missing_fields in gen_finish
+        let invalid_fields = Ident::new("invalid_fields", Span::mixed_site());
+        let validate_struct = self.validate.as_ref().map(|path| {
+            quote! {
+                if let ::core::result::Result::Err(e) = #path(&__built) {
+                    return ::core::result::Result::Err(#error_name {
+                        missing_fields: ::std::vec::Vec::new(),
+                        invalid_fields: ::std::vec::Vec::new(),
+                        invalid_reason: ::core::option::Option::Some(::std::string::ToString::to_string(&e)),
+                    });
+                }
+            }
+        });
+
+        quote! {
+            let mut #invalid_fields = ::std::vec::Vec::new();
+            match &__built {
+                #target { #(#members: #bindings,)* .. } => {
+                    #(
+                        if let ::core::result::Result::Err(e) = #paths(#bindings) {
+                            #invalid_fields.push((#names, ::std::string::ToString::to_string(&e)));
+                        }
+                    )*
+                }
+                #[allow(unreachable_patterns)]
+                _ => {}
+            }
+            if !#invalid_fields.is_empty() {
+                return ::core::result::Result::Err(#error_name {
+                    missing_fields: ::std::vec::Vec::new(),
+                    invalid_fields: #invalid_fields,
+                    invalid_reason: ::core::option::Option::None,
+                });
+            }
+            #validate_struct
+        }
+    }
+
+    // what derive(Debug) would generate, except that fields holding trait objects, e.g. Box<dyn Fn()>,
+    // show up as `..` since those are hardly ever Debug. Type params and the given states must be Debug
+    fn gen_debug(&self, generics: &Generics, builder_ty: TokenStream, states: &[&Ident]) -> TokenStream {
+        let builder_name = self.builder_name.to_string();
+        let mut generics = generics.clone();
+        let params: Vec<Ident> = self
+            .generics
+            .type_params()
+            .map(|p| p.ident.clone())
+            .chain(states.iter().map(|s| (*s).clone()))
+            .collect();
+        let where_clause = generics.make_where_clause();
+        for param in &params {
+            where_clause.predicates.push(parse_quote!(#param: ::core::fmt::Debug));
+        }
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+        let fields = self.fields.iter().map(|f| {
+            let name = &f.name;
+            let label = field_name_str(name);
+            if contains_dyn(&f.ty) {
+                quote! { .field(#label, &::core::format_args!("..")) }
+            } else {
+                quote! { .field(#label, &self.#name) }
+            }
+        });
+
+        quote! {
+            impl #impl_generics ::core::fmt::Debug for #builder_ty #where_clause {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    f.debug_struct(#builder_name)
+                        #(#fields)*
+                        .finish()
+                }
+            }
+        }
+    }
+
+    fn gen_optionized_fields(&self) -> impl Iterator<Item = TokenStream> + '_ {
         self.fields.iter().map(|f| {
-            
-            let (_, ty) = get_option_inner(&f.ty);
+            let ty = f.slot_ty();
             let name = &f.name;
-            quote! { #name: std::option::Option<#ty> }
+            quote! { #name: ::core::option::Option<#ty> }
         })
     }
 
-    fn gen_methods(&self) -> TokenStreamIter {
-        self.fields.iter().map(|f| {
-            let (_, ty) = get_option_inner(&f.ty);
-            let (is_vec, vec_inner_type) = get_vec_inner(&f.ty);
+    fn gen_methods(&self) -> impl Iterator<Item = TokenStream> + '_ {
+        let vis = &self.vis;
+        self.fields.iter().map(move |f| {
+            // in typestate mode required fields get a state-changing setter instead, see gen_typestate_setters
+            if self.typestate && f.is_required() {
+                return quote! {};
+            }
+
+            let ty = f.setter_ty();
             let name = &f.name;
-            if is_vec {
-                if let Some(each_name) = f.opts.each.as_deref() {
-                    let each_name = Ident::new(each_name, f.name.span());
-                    return   quote! {
-                        pub fn #each_name(mut self, v: impl Into<#vec_inner_type>) -> Self { 
-                            let mut data = self.#name.take().unwrap_or_default();
-                            data.push(v.into());
-                            self.#name = Some(data);
-                            self
-                        }
-                    };
+            let this = self.pattern.this();
+
+            // option fields. e.g. executable: String -> executable: Option<String>
+            let (arg, value) = f.setter_arg();
+            let set = f.slot_value(quote! { ::core::option::Option::Some(#value) });
+            let setter = self.pattern.setter(vis, name, quote! { v: #arg }, quote! { #this.#name = #set; });
+            // e.g. fn try_port(v: impl TryInto<u16>), passing the conversion's error on to the caller
+            let try_setter = f.try_setter.then(|| {
+                let try_name = format_ident!("try_{}", field_name_str(name), span = name.span());
+                let set = f.slot_value(quote! {
+                    ::core::option::Option::Some(::core::convert::TryInto::try_into(v)?)
+                });
+                self.pattern.try_setter(vis, &try_name, ty, quote! { #this.#name = #set; })
+            });
+            // e.g. fn maybe_current_dir(v: Option<impl Into<String>>) and fn clear_current_dir()
+            let option_setters = f.option_inner.as_ref().map(|_| {
+                let maybe_name = format_ident!("maybe_{}", field_name_str(name), span = name.span());
+                let clear_name = format_ident!("clear_{}", field_name_str(name), span = name.span());
+                // annotated, so a boxed value is coerced into the trait object
+                let maybe_set = f.slot_value(quote! { ::core::option::Option::map(v, |v| -> #ty { #value }) });
+                let maybe = self.pattern.setter(
+                    vis,
+                    &maybe_name,
+                    quote! { v: ::core::option::Option<#arg> },
+                    quote! { #this.#name = #maybe_set; },
+                );
+                let clear_set = f.slot_value(quote! { ::core::option::Option::None });
+                let clear = self.pattern.setter(vis, &clear_name, quote! {}, quote! { #this.#name = #clear_set; });
+                quote! {
+                    #maybe
+                    #clear
+                }
+            });
+            let setter = quote! {
+                #setter
+                #try_setter
+                #option_setters
+            };
+            let collection = match &f.collection {
+                Some(collection) => collection,
+                None => return setter,
+            };
+
+            // e.g. fn arg(v: impl Into<String>), or fn env(k: impl Into<String>, v: impl Into<String>)
+            let into = f.into.unwrap_or(true);
+            let map_params = |key_ty: &Type, value_ty: &Type| {
+                let (key_arg, key) = into_arg(into, key_ty, quote! { k });
+                let (value_arg, value) = into_arg(into, value_ty, quote! { v });
+                (
+                    quote! { k: #key_arg, v: #value_arg },
+                    quote! { (k, v) },
+                    quote! { (#key, #value) },
+                    quote! { impl ::core::iter::IntoIterator<Item = (#key_arg, #value_arg)> },
+                )
+            };
+            let (params, pat, item, items) = match collection {
+                Collection::Items(item_ty) => {
+                    let (arg, value) = into_arg(into, item_ty, quote! { v });
+                    (
+                        quote! { v: #arg },
+                        quote! { v },
+                        value,
+                        quote! { impl ::core::iter::IntoIterator<Item = #arg> },
+                    )
                 }
+                Collection::Map(key_ty, value_ty) => map_params(key_ty, value_ty),
+                Collection::Entries(item_ty) => {
+                    let entry = self.entry_trait();
+                    map_params(&parse_quote!(<#item_ty as #entry>::Key), &parse_quote!(<#item_ty as #entry>::Value))
+                }
+            };
+            let each = f.each.as_ref().map(|each_name| {
+                self.pattern.setter(
+                    vis,
+                    each_name,
+                    params,
+                    quote! {
+                        let mut data = #this.#name.take().unwrap_or_default();
+                        ::core::iter::Extend::extend(&mut data, ::core::iter::once(#item));
+                        #this.#name = ::core::option::Option::Some(data);
+                    },
+                )
+            });
+
+            // e.g. fn extend_args(iter: impl IntoIterator<Item = impl Into<String>>)
+            let extend_name = format_ident!("extend_{}", field_name_str(name), span = name.span());
+            let extend = self.pattern.setter(
+                vis,
+                &extend_name,
+                quote! { iter: #items },
+                quote! {
+                    let mut data = #this.#name.take().unwrap_or_default();
+                    ::core::iter::Extend::extend(
+                        &mut data,
+                        ::core::iter::Iterator::map(::core::iter::IntoIterator::into_iter(iter), |#pat| #item),
+                    );
+                    #this.#name = ::core::option::Option::Some(data);
+                },
+            );
+
+            quote! {
+                #setter
+                #each
+                #extend
             }
+        })
+    }
 
-            // option fields. e.g. executable: String -> executable: Option<String>
+    // e.g. match (self.executable.take(),) {
+    //     (Some(executable),) => Ok(Command { executable, .. }),
+    //     (executable,) => Err(CommandBuilderError { missing_fields: vec!["executable"] }),
+    // }
+    fn gen_finish(&self) -> TokenStream {
+        let target = self.target();
+        let error_name = self.error_name();
+        let assigns = self.gen_assigns();
+        let required: Vec<&Ident> = self
+            .fields
+            .iter()
+            .filter(|f| f.is_required())
+            .map(|f| &f.name)
+            .collect();
+        let required_names = required.iter().map(|name| field_name_str(name));
+        let values = required.iter().map(|name| self.pattern.build_value(name));
+        // mixed_site, so a required field called missing_fields doesn't shadow it
+        let missing_fields = Ident::new("missing_fields", Span::mixed_site());
+        let built = if self.struct_default {
+            self.gen_struct_default(|name| self.pattern.build_value(name))
+        } else {
             quote! {
-                pub fn #name(mut self, v: impl Into<#ty>) -> Self {
-                    self.#name = Some(v.into());
-                    self
+                #target {
+                    #(#assigns,)*
+                }
+            }
+        };
+        let built = if self.has_validators() {
+            let validate = self.gen_validate();
+            quote! {{
+                let __built = #built;
+                #validate
+                __built
+            }}
+        } else {
+            built
+        };
+
+        quote! {
+            match (#(#values,)*) {
+                (#(::core::option::Option::Some(#required),)*) => ::core::result::Result::Ok(#built),
+                #[allow(unreachable_patterns)]
+                (#(#required,)*) => {
+                    let mut #missing_fields = ::std::vec::Vec::new();
+                    #(
+                        if #required.is_none() {
+                            #missing_fields.push(#required_names);
+                        }
+                    )*
+                    ::core::result::Result::Err(#error_name {
+                        missing_fields: #missing_fields,
+                        invalid_fields: ::std::vec::Vec::new(),
+                        invalid_reason: ::core::option::Option::None,
+                    })
+                }
+            }
+        }
+    }
+
+    // #[builder(default)] on the struct: start from its Default and only replace what was set, e.g.
+    // { let mut __default: Command = Default::default(); if let Some(v) = self.executable.take() { .. } __default }
+    fn gen_struct_default(&self, value: impl Fn(&Ident) -> TokenStream) -> TokenStream {
+        let name = &self.name;
+        let (_, ty_generics, _) = self.generics.split_for_impl();
+        let assigns = self.fields.iter().map(|f| {
+            let member = &f.member;
+            let value = value(&f.name);
+            if let Some(default) = &f.default {
+                // a field's own default wins over the struct's
+                let value = f.defaulted_value(value, default);
+                quote! { __default.#member = #value; }
+            } else {
+                quote! {
+                    if let ::core::option::Option::Some(v) = #value {
+                        __default.#member = v;
+                    }
                 }
             }
+        });
+        // a skipped field keeps the struct's Default unless it has its own
+        let skipped = self.skipped.iter().filter_map(|f| {
+            let member = &f.member;
+            let default = f.default.as_ref()?;
+            Some(quote! { __default.#member = #default; })
+        });
+
+        quote! {{
+            let mut __default: #name #ty_generics = ::core::default::Default::default();
+            #(#skipped)*
+            #(#assigns)*
+            __default
+        }}
+    }
+
+    // skipped fields are never set, e.g. cache: Cache::new(), or Default::default() without a default
+    fn gen_skipped_assigns(&self) -> impl Iterator<Item = TokenStream> + '_ {
+        self.skipped.iter().map(|f| {
+            let member = &f.member;
+            match &f.default {
+                Some(default) => quote! { #member: #default },
+                None => quote! { #member: ::core::default::Default::default() },
+            }
         })
     }
 
-    fn gen_assigns(&self) -> TokenStreamIter {
+    // generic structs may only be Default for some params, e.g. impl<T: Default> Default for Config<T>
+    fn gen_build_where(&self) -> TokenStream {
+        let name = &self.name;
+        let (_, ty_generics, _) = self.generics.split_for_impl();
+        if self.struct_default {
+            quote! { where #name #ty_generics: ::core::default::Default }
+        } else {
+            quote! {}
+        }
+    }
+
+    // with #[builder(runtime)], impl Buildable for the struct and Builder for its builder, e.g.
+    // impl Builder for CommandBuilder { type Output = Command; type Error = CommandBuilderError; .. }
+    // an enum has a builder per variant and none of them is *the* builder, so only those implement Builder
+    fn gen_trait_impls(
+        &self,
+        unset_builder: TokenStream,
+        set_builder: TokenStream,
+        error: TokenStream,
+        build: TokenStream,
+    ) -> TokenStream {
+        let runtime = match &self.runtime {
+            Some(runtime) => runtime,
+            None => return quote! {},
+        };
+        let name = &self.name;
+        let constructor_name = &self.constructor_name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+
+        // the build method's own bound, see gen_build_where
+        let mut build_generics = self.generics.clone();
+        if self.struct_default {
+            build_generics
+                .make_where_clause()
+                .predicates
+                .push(parse_quote!(#name #ty_generics: ::core::default::Default));
+        }
+        let (_, _, build_where_clause) = build_generics.split_for_impl();
+
+        let buildable = match self.variant {
+            Some(_) => quote! {},
+            None => quote! {
+                impl #impl_generics #runtime::Buildable for #name #ty_generics #where_clause {
+                    type Builder = #unset_builder;
+
+                    fn builder() -> Self::Builder {
+                        <#name #ty_generics>::#constructor_name()
+                    }
+                }
+            },
+        };
+
+        quote! {
+            #buildable
+
+            impl #impl_generics #runtime::Builder for #set_builder #build_where_clause {
+                type Output = #name #ty_generics;
+                type Error = #error;
+
+                fn build(self) -> ::core::result::Result<Self::Output, Self::Error> {
+                    #build
+                }
+            }
+        }
+    }
+
+    // impl From<Command> for CommandBuilder and Command::to_builder, every slot is set to the field's value.
+    // skipped fields get their default again, and an enum's value may be any of its variants, so only a
+    // struct converts back
+    fn gen_to_builder(&self, set_builder: TokenStream) -> TokenStream {
+        if self.variant.is_some() {
+            return quote! {};
+        }
+
+        let name = &self.name;
+        let vis = &self.vis;
+        let builder_name = &self.builder_name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        // e.g. executable: Some(v), or executable: (v,) for a typestate builder
+        let slot = |f: &Fd, v: TokenStream| {
+            let name = &f.name;
+            if self.typestate && f.is_required() {
+                quote! { #name: (#v,) }
+            } else if f.option_inner.is_some() {
+                let v = f.slot_value(v);
+                quote! { #name: #v }
+            } else {
+                quote! { #name: ::core::option::Option::Some(#v) }
+            }
+        };
+        let moves = self.fields.iter().map(|f| {
+            let member = &f.member;
+            slot(f, quote! { value.#member })
+        });
+        let clones = self.fields.iter().map(|f| {
+            let member = &f.member;
+            slot(f, quote! { ::core::clone::Clone::clone(&self.#member) })
+        });
+        // for<'__b> keeps the bounds from being checked up front, so a struct with a field that isn't
+        // Clone still compiles, it just can't call to_builder
+        let clone_bounds = self.fields.iter().map(|f| {
+            let ty = &f.ty;
+            quote! { for<'__b> #ty: ::core::clone::Clone }
+        });
+
+        quote! {
+            impl #impl_generics ::core::convert::From<#name #ty_generics> for #set_builder #where_clause {
+                fn from(value: #name #ty_generics) -> Self {
+                    #builder_name {
+                        #(#moves,)*
+                        __marker: ::core::marker::PhantomData,
+                    }
+                }
+            }
+
+            impl #impl_generics #name #ty_generics #where_clause {
+                /// A builder holding a copy of every field, e.g. to change just one of them
+                #vis fn to_builder(&self) -> #set_builder
+                where
+                    #(#clone_bounds,)*
+                {
+                    #builder_name {
+                        #(#clones,)*
+                        __marker: ::core::marker::PhantomData,
+                    }
+                }
+            }
+        }
+    }
+
+    fn gen_assigns(&self) -> impl Iterator<Item = TokenStream> + '_ {
         self.fields.iter().map(|f| {
             let name = &f.name;
-            let (optional, _) = get_option_inner(&f.ty);
-            if optional {
+            let member = &f.member;
+            let value = self.pattern.build_value(name);
+            if let Some(default) = &f.default {
+                let value = f.defaulted_value(value, default);
+                return quote! { #member: #value };
+            }
+
+            if f.option_inner.is_some() {
                 return quote! {
-                    #name: self.#name.take()
+                    #member: #value
                 };
             }
 
-            if let Some(default) = f.opts.default.as_deref() {
-                let ast : TokenStream = default.parse().unwrap();
-                return quote! { #name: self.#name.take().unwrap_or_else(|| #ast)}
+            // the slot holds the field's Option, e.g. Option<Option<String>>
+            if f.optional {
+                return quote! { #member: ::core::option::Option::flatten(#value) };
             }
 
-            // field_name: self.#field_name.take().ok_or(" xx need to be set!")
+            // required fields were already taken out, see gen_finish
+            quote! { #member: #name }
+        })
+        .chain(self.gen_skipped_assigns())
+    }
+
+    // typestate builder: every required field gets a type parameter which is `()` until the
+    // field is set and `(T,)` afterwards, e.g. CommandBuilder<(), ..> -> CommandBuilder<(String,), ..>.
+    // finish only exists once all of them are set, and a setter only exists while its field is unset.
+    fn generate_typestate(&self) -> TokenStream {
+        let name = &self.name;
+        let vis = &self.vis;
+        let builder_name = &self.builder_name;
+        let constructor_name = &self.constructor_name;
+        let build_fn = &self.build_fn;
+        let target = self.target();
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        let args = generic_args(&self.generics);
+
+        let required: Vec<&Fd> = self.fields.iter().filter(|f| f.is_required()).collect();
+        // one state parameter per required field, numbered by its position, e.g. executable -> __S0
+        let states: Vec<Ident> = self
+            .fields
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.is_required())
+            .map(|(i, _)| state_ident(i))
+            .collect();
+
+        // builder struct: the struct's generics plus the states, which start out unset
+        let mut struct_generics = self.generics.clone();
+        struct_generics.params.extend(
+            states.iter().map(|s| -> GenericParam { parse_quote!(#s = ()) }),
+        );
+        let slots = self.fields.iter().enumerate().map(|(i, f)| {
+            let name = &f.name;
+            if f.is_required() {
+                let state = state_ident(i);
+                quote! { #name: #state }
+            } else {
+                let ty = f.slot_ty();
+                quote! { #name: ::core::option::Option<#ty> }
+            }
+        });
+
+        // setters of optional and defaulted fields keep the state as it is
+        let mut any_state = self.generics.clone();
+        any_state.params.extend(states.iter().map(|s| -> GenericParam { parse_quote!(#s) }));
+        let (any_impl_generics, _, _) = any_state.split_for_impl();
+        let methods = self.gen_methods();
+        // the states of trait object fields aren't Debug, see gen_debug
+        let debug_states: Vec<&Ident> = required
+            .iter()
+            .zip(&states)
+            .filter(|(f, _)| !contains_dyn(&f.ty))
+            .map(|(_, s)| s)
+            .collect();
+        let debug = self.gen_debug(
+            &any_state,
+            quote! { #builder_name <#(#args,)* #(#states),*> },
+            &debug_states,
+        );
+
+        let setters = self.gen_typestate_setters(&required, &states);
+
+        // finish: every state is set, e.g. CommandBuilder<(String,)>
+        let set_states: Vec<TokenStream> = required
+            .iter()
+            .map(|f| {
+                let ty = &f.ty;
+                quote! { (#ty,) }
+            })
+            .collect();
+        let assigns = self.fields.iter().map(|f| {
+            let name = &f.name;
+            let member = &f.member;
+            if f.is_required() {
+                quote! { #member: self.#name.0 }
+            } else if let Some(default) = &f.default {
+                let value = f.defaulted_value(quote! { self.#name }, default);
+                quote! { #member: #value }
+            } else if f.optional {
+                quote! { #member: ::core::option::Option::flatten(self.#name) }
+            } else {
+                quote! { #member: self.#name }
+            }
+        })
+        .chain(self.gen_skipped_assigns());
+        let build_where = self.gen_build_where();
+        let built = if self.struct_default {
+            // nothing is required here, so every slot is still an Option
+            self.gen_struct_default(|name| quote! { self.#name })
+        } else {
             quote! {
-                #name: self.#name.take().ok_or(concat!(stringify!(#name), " needs to be set!"))?
+                #target {
+                    #(#assigns,)*
+                }
+            }
+        };
+        // with validators finish can fail after all, so it returns a Result like the other builders
+        let (build_ty, built, error) = if self.has_validators() {
+            let error_name = self.error_name();
+            let validate = self.gen_validate();
+            (
+                quote! { ::core::result::Result<#name #ty_generics, #error_name> },
+                quote! {
+                    let __built = #built;
+                    #validate
+                    ::core::result::Result::Ok(__built)
+                },
+                self.gen_error(),
+            )
+        } else {
+            (quote! { #name #ty_generics }, built, quote! {})
+        };
+
+        // builder: every state is unset, e.g. CommandBuilder<()>
+        let unset_states: Vec<TokenStream> = states.iter().map(|_| quote! { () }).collect();
+        // a builder with every state set has a finish that can't fail, unless there are validators
+        let (trait_error, trait_build) = if self.has_validators() {
+            let error_name = self.error_name();
+            (quote! { #error_name }, quote! { Self::#build_fn(self) })
+        } else {
+            (
+                quote! { ::core::convert::Infallible },
+                quote! { ::core::result::Result::Ok(Self::#build_fn(self)) },
+            )
+        };
+        let trait_impls = self.gen_trait_impls(
+            quote! { #builder_name <#(#args,)* #(#unset_states),*> },
+            quote! { #builder_name <#(#args,)* #(#set_states),*> },
+            trait_error,
+            trait_build,
+        );
+        let to_builder = self.gen_to_builder(quote! { #builder_name <#(#args,)* #(#set_states),*> });
+        let entry_trait = self.gen_entry_trait();
+        let empty_fields = self.fields.iter().map(|f| {
+            let name = &f.name;
+            if f.is_required() {
+                quote! { #name: () }
+            } else {
+                quote! { #name: ::core::option::Option::None }
+            }
+        });
+
+        quote! {
+            /// Builder structure
+            #vis struct #builder_name #struct_generics #where_clause {
+                #(#slots,)*
+                // required fields only show up in the states, and an enum variant may not use all
+                // of the enum's params, keep them in use
+                __marker: ::core::marker::PhantomData<fn() -> #name #ty_generics>,
+            }
+
+            #debug
+
+            impl #any_impl_generics #builder_name <#(#args,)* #(#states),*> #where_clause {
+                #(#methods)*
+            }
+
+            #(#setters)*
+
+            impl #impl_generics #builder_name <#(#args,)* #(#set_states),*> #where_clause {
+                #vis fn #build_fn(self) -> #build_ty
+                #build_where
+                {
+                    #built
+                }
+            }
+
+            #error
+
+            #trait_impls
+
+            #to_builder
+
+            #entry_trait
+
+            impl #impl_generics #name #ty_generics #where_clause {
+                #vis fn #constructor_name() -> #builder_name <#(#args,)* #(#unset_states),*> {
+                    #builder_name {
+                        #(#empty_fields,)*
+                        __marker: ::core::marker::PhantomData,
+                    }
+                }
+            }
+        }
+    }
+
+    // one impl per required field, only for builders where that field is unset, e.g.
+    // impl<__S1> CommandBuilder<(), __S1> { fn executable(self, ..) -> CommandBuilder<(String,), __S1> }
+    fn gen_typestate_setters(&self, required: &[&Fd], states: &[Ident]) -> Vec<TokenStream> {
+        let vis = &self.vis;
+        let builder_name = &self.builder_name;
+        let where_clause = &self.generics.where_clause;
+        let args = generic_args(&self.generics);
+        let names: Vec<&Ident> = self.fields.iter().map(|f| &f.name).collect();
+
+        required
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                let name = &f.name;
+                let ty = &f.ty;
+                let (arg, value) = f.setter_arg();
+
+                let mut generics = self.generics.clone();
+                generics.params.extend(
+                    states
+                        .iter()
+                        .enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .map(|(_, s)| -> GenericParam { parse_quote!(#s) }),
+                );
+                let (impl_generics, _, _) = generics.split_for_impl();
+
+                let before = states.iter().enumerate().map(|(j, s)| {
+                    if j == i { quote! { () } } else { quote! { #s } }
+                });
+                let after = states.iter().enumerate().map(|(j, s)| {
+                    if j == i { quote! { (#ty,) } } else { quote! { #s } }
+                });
+                let moves: Vec<TokenStream> = names
+                    .iter()
+                    .map(|n| if *n == name { quote! { #n: (v,) } } else { quote! { #n: self.#n } })
+                    .collect();
+                let marker = quote! { __marker: self.__marker };
+                let after: Vec<TokenStream> = after.collect();
+
+                // e.g. fn try_port<__V: TryInto<u16>>(self, v: __V) -> Result<CommandBuilder<(u16,)>, __V::Error>
+                let try_setter = f.try_setter.then(|| {
+                    let try_name = format_ident!("try_{}", field_name_str(name), span = name.span());
+                    quote! {
+                        #vis fn #try_name<__V: ::core::convert::TryInto<#ty>>(
+                            self,
+                            v: __V,
+                        ) -> ::core::result::Result<
+                            #builder_name <#(#args,)* #(#after),*>,
+                            <__V as ::core::convert::TryInto<#ty>>::Error,
+                        > {
+                            let v = ::core::convert::TryInto::try_into(v)?;
+                            ::core::result::Result::Ok(#builder_name {
+                                #(#moves,)*
+                                #marker,
+                            })
+                        }
+                    }
+                });
+
+                quote! {
+                    impl #impl_generics #builder_name <#(#args,)* #(#before),*> #where_clause {
+                        #vis fn #name(self, v: #arg) -> #builder_name <#(#args,)* #(#after),*> {
+                            let v = #value;
+                            #builder_name {
+                                #(#moves,)*
+                                #marker,
+                            }
+                        }
+
+                        #try_setter
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+// generic arguments naming the params of a type, e.g. <'a, T: Clone, const N: usize> -> 'a, T, N
+fn generic_args(generics: &Generics) -> Vec<TokenStream> {
+    generics
+        .params
+        .iter()
+        .map(|param| match param {
+            GenericParam::Lifetime(l) => {
+                let lifetime = &l.lifetime;
+                quote! { #lifetime }
+            }
+            GenericParam::Type(t) => {
+                let ident = &t.ident;
+                quote! { #ident }
+            }
+            GenericParam::Const(c) => {
+                let ident = &c.ident;
+                quote! { #ident }
             }
         })
+        .collect()
+}
+
+// whether `vis` may reach less far than `than`, e.g. pub(crate) than pub. two different modules,
+// e.g. pub(super) and pub(in crate::config), can't be told apart here, so they count as narrower
+fn is_narrower(vis: &Visibility, than: &Visibility) -> bool {
+    // private, restricted to some module, the crate, everywhere
+    fn reach(vis: &Visibility) -> u8 {
+        match vis {
+            Visibility::Inherited => 0,
+            Visibility::Restricted(r) if r.path.is_ident("self") => 0,
+            Visibility::Restricted(r) if r.path.is_ident("crate") => 2,
+            Visibility::Restricted(_) => 1,
+            Visibility::Crate(_) => 2,
+            Visibility::Public(_) => 3,
+        }
+    }
+    match (reach(vis), reach(than)) {
+        (1, 1) => vis.to_token_stream().to_string() != than.to_token_stream().to_string(),
+        (vis, than) => vis < than,
     }
 }
 
-fn get_option_inner(ty: &Type) -> (bool, &Type) {
-    get_type_inner(ty, "Option")
+// name of a field as users wrote it, e.g. r#type -> "type"
+fn field_name_str(name: &Ident) -> String {
+    name.to_string().trim_start_matches("r#").to_string()
 }
 
+// e.g. DoubleClick -> double_click
+fn snake_case(name: &Ident) -> String {
+    let mut snake = String::new();
+    for (i, c) in field_name_str(name).chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
 
-fn get_vec_inner(ty: &Type) -> (bool, &Type) {
-    get_type_inner(ty, "Vec")
+// name of the typestate parameter of the field at `index`, e.g. __S1 for the second field. from the index
+// rather than the name, which can't be camel-cased without clashes, e.g. x_1 and x1
+fn state_ident(index: usize) -> Ident {
+    format_ident!("__S{}", index)
 }
 
+// one `key`, `key = value` or `key(..)` entry of a #[builder(..)] attribute
+struct AttrArg {
+    key: Ident,
+    value: AttrValue,
+}
 
-fn get_type_inner<'a>(ty: &'a Type, name: &str) -> (bool, &'a Type) {
-    if let Type::Path(TypePath { path: Path {segments, ..}, ..}) = ty {
-        if let Some(v) = segments.first() {
-            if v.ident == name {
-                let t = match &v.arguments {
-                    PathArguments::AngleBracketed(a) => match a.args.iter().next() {
-                        Some(GenericArgument::Type(t)) => t,
-                        _ => panic!("Not sure what to do with other GenericArgument"),
-                    },
-                    _ => panic!("Not sure what to do with other PathArguments"),
-                };
-                return (true, t);   
+enum AttrValue {
+    Flag,
+    // unlike darling, any expression is accepted, e.g. default = vec![]
+    Expr(Token![=], Box<Expr>),
+    List(TokenTree),
+}
+
+impl Parse for AttrArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key = input.call(Ident::parse_any)?;
+        let value = if input.peek(Token![=]) {
+            AttrValue::Expr(input.parse()?, input.parse()?)
+        } else if input.peek(token::Paren) {
+            AttrValue::List(input.parse()?)
+        } else {
+            AttrValue::Flag
+        };
+        Ok(Self { key, value })
+    }
+}
+
+impl ToTokens for AttrArg {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        self.key.to_tokens(tokens);
+        match &self.value {
+            AttrValue::Flag => {}
+            AttrValue::Expr(eq, expr) => {
+                eq.to_tokens(tokens);
+                expr.to_tokens(tokens);
             }
+            AttrValue::List(list) => list.to_tokens(tokens),
         }
     }
-    
-    return (false, ty);
-}
\ No newline at end of file
+}
+
+// `default` of a field may be any expression, e.g. #[builder(default = vec![])], a bare #[builder(default)]
+// for Default::default(), or a string with the expression, e.g. #[builder(default = "vec![]")].
+// darling only understands literal values, so it is taken out of the attribute before darling sees it
+fn take_default(attrs: &mut [Attribute]) -> darling::Result<Option<Expr>> {
+    let mut errors = darling::Error::accumulator();
+    let mut default = None;
+    for attr in attrs.iter_mut().filter(|attr| attr.path.is_ident("builder")) {
+        let args = match attr.parse_args_with(Punctuated::<AttrArg, Token![,]>::parse_terminated) {
+            Ok(args) => args,
+            Err(e) => {
+                // reported here, darling would only report it a second time
+                attr.tokens = quote! { () };
+                errors.push(e.into());
+                continue;
+            }
+        };
+        let mut rest = Vec::new();
+        for arg in args {
+            if arg.key != "default" {
+                rest.push(arg);
+                continue;
+            }
+            // a broken `default` is left out all the same, darling doesn't know the key
+            let value = match arg.value {
+                AttrValue::Flag => Ok(parse_quote!(::core::default::Default::default())),
+                AttrValue::Expr(_, expr) => match *expr {
+                    Expr::Lit(ExprLit { lit: Lit::Str(lit), .. }) => parse_lit_str(&lit, "`default`"),
+                    expr => Ok(expr),
+                },
+                AttrValue::List(list) => Err(syn::Error::new_spanned(
+                    list,
+                    "expected `default` or `default = ...`",
+                )),
+            };
+            match value {
+                Ok(_) if default.is_some() => {
+                    errors.push(syn::Error::new_spanned(&arg.key, "duplicate `default`").into())
+                }
+                Ok(value) => default = Some(value),
+                Err(e) => errors.push(e.into()),
+            }
+        }
+        attr.tokens = quote! { (#(#rest),*) };
+    }
+    errors.finish_with(default)
+}
+
+// parse the contents of a string attribute value, e.g. default = "vec![]" -> vec![]
+fn parse_lit_str<T: Parse>(lit: &LitStr, what: &str) -> syn::Result<T> {
+    // errors from inside the string have no useful span of their own, so point at the whole literal
+    lit.parse()
+        .map_err(|e| syn::Error::new(lit.span(), format!("invalid {}: {}", what, e)))
+}
+
+fn get_option_inner(ty: &Type) -> syn::Result<Option<&Type>> {
+    Ok(get_type_args(ty, &["std", "core"], "Option", 1)?.map(|args| args[0]))
+}
+
+// e.g. (impl Into<String>, Into::into(v)), or (String, v) with #[builder(setter(into = false))]
+fn into_arg(into: bool, ty: &Type, v: TokenStream) -> (TokenStream, TokenStream) {
+    match into {
+        true => (quote! { impl ::core::convert::Into<#ty> }, quote! { ::core::convert::Into::into(#v) }),
+        false => (quote! { #ty }, v),
+    }
+}
+
+// the bounds of a boxed trait object, e.g. Box<dyn Fn(u8) -> u8 + Send> -> Fn(u8) -> u8 + Send
+fn get_boxed_dyn(ty: &Type) -> Option<&Punctuated<TypeParamBound, Token![+]>> {
+    match get_type_args(ty, &["std", "alloc"], "Box", 1) {
+        Ok(Some(args)) => match args[0] {
+            Type::TraitObject(TypeTraitObject { bounds, .. }) => Some(bounds),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// e.g. Box<dyn Fn()> or Vec<Box<dyn Error>>
+fn contains_dyn(ty: &Type) -> bool {
+    fn walk(tokens: TokenStream) -> bool {
+        tokens.into_iter().any(|t| match t {
+            TokenTree::Ident(ident) => ident == "dyn",
+            TokenTree::Group(group) => walk(group.stream()),
+            _ => false,
+        })
+    }
+    walk(ty.to_token_stream())
+}
+
+// e.g. PhantomData<T> or std::marker::PhantomData<fn() -> T>
+fn is_phantom_data(ty: &Type) -> bool {
+    matches!(get_type_args(ty, &["std", "core"], "PhantomData", 1), Ok(Some(_)))
+}
+
+// e.g. Vec<String> -> Items(String), std::collections::HashMap<String, u8> -> Map(String, u8)
+fn get_collection(ty: &Type) -> syn::Result<Option<Collection>> {
+    const ITEMS: &[&str] = &["Vec", "VecDeque", "LinkedList", "BinaryHeap", "HashSet", "BTreeSet"];
+    const MAPS: &[&str] = &["HashMap", "BTreeMap"];
+
+    for name in ITEMS {
+        if let Some(args) = get_type_args(ty, &["std", "alloc"], name, 1)? {
+            return Ok(Some(Collection::Items(args[0].clone())));
+        }
+    }
+    for name in MAPS {
+        if let Some(args) = get_type_args(ty, &["std", "alloc"], name, 2)? {
+            return Ok(Some(Collection::Map(args[0].clone(), args[1].clone())));
+        }
+    }
+    Ok(None)
+}
+
+// the first `count` type arguments of a std type, e.g. get_type_args(HashMap<String, u8>, .., "HashMap", 2)
+// -> Some([String, u8]), get_type_args(String, ..) -> None. The type may also be spelled by its full path,
+// e.g. std::collections::hash_map::HashMap<String, u8>
+fn get_type_args<'a>(
+    ty: &'a Type,
+    crates: &[&str],
+    name: &str,
+    count: usize,
+) -> syn::Result<Option<Vec<&'a Type>>> {
+    let segments = match ty {
+        Type::Path(TypePath { qself: None, path: Path { segments, .. } }) => segments,
+        _ => return Ok(None),
+    };
+    let matches = match (segments.first(), segments.last()) {
+        (Some(first), Some(last)) if segments.len() == 1 => first.ident == name && last.ident == name,
+        (Some(first), Some(last)) => crates.iter().any(|c| first.ident == c) && last.ident == name,
+        _ => false,
+    };
+    if !matches {
+        return Ok(None);
+    }
+
+    let v = segments.last().expect("matched path has segments");
+    let args = match &v.arguments {
+        PathArguments::AngleBracketed(a) => a,
+        _ => return Err(syn::Error::new_spanned(v, expected_args(name, count))),
+    };
+    let mut types = Vec::with_capacity(count);
+    for arg in args.args.iter().take(count) {
+        match arg {
+            GenericArgument::Type(t) => types.push(t),
+            arg => {
+                return Err(syn::Error::new_spanned(
+                    arg,
+                    format!("expected a type argument of `{}`", name),
+                ))
+            }
+        }
+    }
+    if types.len() < count {
+        return Err(syn::Error::new_spanned(args, expected_args(name, count)));
+    }
+    Ok(Some(types))
+}
+
+// e.g. expected `Option<T>`, expected `HashMap<K, V>`
+fn expected_args(name: &str, count: usize) -> String {
+    let params = if count == 1 { "T" } else { "K, V" };
+    format!("expected `{}<{}>`", name, params)
+}
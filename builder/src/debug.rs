@@ -0,0 +1,66 @@
+use std::env;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+use proc_macro2::Ident;
+use proc_macro2::TokenStream;
+
+/// Whether BUILDER_DEBUG_EXPAND asks for the expansion of this struct, e.g.
+/// BUILDER_DEBUG_EXPAND=1 for every struct, or BUILDER_DEBUG_EXPAND=Command,Event for just those.
+/// cargo doesn't know the derive reads it, so only crates that get rebuilt are written
+pub fn requested(name: &Ident) -> bool {
+    match env::var("BUILDER_DEBUG_EXPAND") {
+        Ok(names) if names == "1" => true,
+        Ok(names) => names.split(',').any(|n| name == n.trim()),
+        Err(_) => false,
+    }
+}
+
+/// Write the generated code of a struct to target/builder/{crate}/{Name}.rs, formatted by rustfmt
+/// when there is one
+pub fn write_expansion(name: &Ident, tokens: &TokenStream) -> io::Result<()> {
+    let mut dir = target_dir().join("builder");
+    if let Ok(krate) = env::var("CARGO_CRATE_NAME") {
+        dir.push(krate);
+    }
+    std::fs::create_dir_all(&dir)?;
+
+    let code = tokens.to_string();
+    let code = rustfmt(&code).unwrap_or(code);
+    std::fs::write(dir.join(format!("{}.rs", name)), code)
+}
+
+// CARGO_TARGET_DIR, or else the closest target/ above the crate, which is the workspace's for a member
+fn target_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("CARGO_TARGET_DIR") {
+        return PathBuf::from(dir);
+    }
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+    manifest_dir
+        .ancestors()
+        .map(|dir| dir.join("target"))
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| manifest_dir.join("target"))
+}
+
+// None if rustfmt isn't installed or can't make sense of the code
+fn rustfmt(code: &str) -> Option<String> {
+    let rustfmt = env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+    let mut child = Command::new(Path::new(&rustfmt))
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(code.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
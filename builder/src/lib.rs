@@ -1,13 +1,15 @@
 mod builder;
+mod debug;
 
-use crate::builder::BuilderContext;
+use crate::builder::expand;
 use proc_macro::TokenStream;
 use syn::{parse_macro_input, DeriveInput};
 
 #[proc_macro_derive(Builder, attributes(builder))]
 pub fn derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    println!("{:#?}", input);
-    let context = BuilderContext::new(input);
-    context.generate().into()
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
 }
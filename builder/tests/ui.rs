@@ -0,0 +1,7 @@
+// what the derive accepts and rejects at compile time, TRYBUILD=overwrite cargo test updates the .stderr files
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/pass/*.rs");
+    t.compile_fail("tests/ui/fail/*.rs");
+}
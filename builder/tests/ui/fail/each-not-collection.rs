@@ -0,0 +1,9 @@
+use builder::Builder;
+
+#[derive(Builder)]
+pub struct Command {
+    #[builder(each = "arg")]
+    args: String,
+}
+
+fn main() {}
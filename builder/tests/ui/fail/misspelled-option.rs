@@ -0,0 +1,11 @@
+use builder::Builder;
+
+#[derive(Builder)]
+pub struct Command {
+    #[builder(eachh = "arg")]
+    args: Vec<String>,
+    #[builder(defualt = 8080)]
+    port: u16,
+}
+
+fn main() {}
@@ -0,0 +1,21 @@
+use builder::Builder;
+
+#[derive(Builder)]
+#[builder(runtime, vis = "pub(crate)")]
+pub struct Server {
+    port: u16,
+}
+
+#[derive(Builder)]
+#[builder(runtime, vis = "pub")]
+pub(crate) struct Database {
+    pool_size: u32,
+}
+
+#[derive(Builder)]
+#[builder(runtime, vis = "pub")]
+pub(crate) enum Event {
+    Click { x: i32 },
+}
+
+fn main() {}
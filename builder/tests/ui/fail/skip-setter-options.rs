@@ -0,0 +1,9 @@
+use builder::Builder;
+
+#[derive(Builder)]
+pub struct Server {
+    #[builder(skip, setter(into = false), strip_option = false, name = "cached")]
+    cache: Option<u8>,
+}
+
+fn main() {}
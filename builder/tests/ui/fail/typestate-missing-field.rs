@@ -0,0 +1,12 @@
+use builder::Builder;
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Command {
+    executable: String,
+    current_dir: Option<String>,
+}
+
+fn main() {
+    let _ = Command::builder().current_dir("/tmp").finish();
+}
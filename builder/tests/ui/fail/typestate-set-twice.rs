@@ -0,0 +1,11 @@
+use builder::Builder;
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Command {
+    executable: String,
+}
+
+fn main() {
+    let _ = Command::builder().executable("find").executable("ls").finish();
+}
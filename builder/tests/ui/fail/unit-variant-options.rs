@@ -0,0 +1,12 @@
+use builder::Builder;
+
+#[derive(Builder)]
+pub enum Event {
+    Click { x: i32 },
+    #[builder(nme = "Y")]
+    Wheel,
+    #[builder(name = "QuitSpec")]
+    Quit,
+}
+
+fn main() {}
@@ -0,0 +1,25 @@
+use builder::Builder;
+
+fn not_empty(v: &[String]) -> Result<(), &'static str> {
+    if v.is_empty() {
+        Err("is empty")
+    } else {
+        Ok(())
+    }
+}
+
+// fields named like the locals of the generated finish
+#[derive(Builder)]
+pub struct Report {
+    #[builder(validate = "not_empty")]
+    missing_fields: Vec<String>,
+    invalid_fields: Vec<String>,
+}
+
+fn main() {
+    let error = Report::builder().finish().err().unwrap();
+    assert_eq!(error.missing_fields(), ["missing_fields", "invalid_fields"]);
+
+    let error = Report::builder().missing_fields(vec![]).invalid_fields(vec![]).finish().err().unwrap();
+    assert_eq!(error.invalid_fields().len(), 1);
+}
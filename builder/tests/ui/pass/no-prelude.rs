@@ -0,0 +1,28 @@
+#![no_implicit_prelude]
+
+extern crate builder;
+extern crate std;
+
+use std::string::String;
+use std::string::ToString;
+
+fn not_empty(v: &String) -> std::result::Result<(), &'static str> {
+    if v.is_empty() {
+        std::result::Result::Err("is empty")
+    } else {
+        std::result::Result::Ok(())
+    }
+}
+
+// nothing of the generated code may rely on the prelude
+#[derive(builder::Builder)]
+pub struct Command {
+    #[builder(validate = "not_empty")]
+    executable: String,
+    current_dir: std::option::Option<String>,
+}
+
+fn main() {
+    let error = Command::builder().executable("").finish().err().unwrap();
+    std::assert_eq!(error.to_string(), "Command has invalid fields: executable (is empty)");
+}
@@ -0,0 +1,47 @@
+use builder::Builder;
+
+#[derive(Debug, Clone)]
+struct NotDefault(u8);
+
+fn fallback_port() -> Option<u16> {
+    Some(3000)
+}
+
+// `default` is always the field's own value, so an Option field's is an Option too
+#[derive(Builder, Clone)]
+pub struct Server {
+    #[builder(default = Some(8080))]
+    port: Option<u16>,
+    #[builder(default_fn = "fallback_port")]
+    admin_port: Option<u16>,
+    #[builder(default)]
+    timeout: Option<u16>,
+    #[builder(default)]
+    tag: Option<NotDefault>,
+    #[builder(strip_option = false, default = Some(1))]
+    workers: Option<u8>,
+    #[builder(skip, default = Some(2))]
+    cache: Option<u8>,
+    #[builder(skip, default)]
+    pool: Option<NotDefault>,
+}
+
+fn main() {
+    let server = Server::builder().finish().unwrap();
+    assert_eq!(server.port, Some(8080));
+    assert_eq!(server.admin_port, Some(3000));
+    assert_eq!(server.timeout, None);
+    assert!(server.tag.is_none());
+    assert_eq!(server.workers, Some(1));
+    assert_eq!(server.cache, Some(2));
+    assert!(server.pool.is_none());
+
+    // cleared is not the same as unset
+    let server = Server::builder().port(1u16).clear_port().workers(None).finish().unwrap();
+    assert_eq!(server.port, None);
+    assert_eq!(server.workers, None);
+    let server = Server::builder().maybe_port(None::<u16>).tag(NotDefault(7)).finish().unwrap();
+    assert_eq!(server.port, None);
+    assert_eq!(server.tag.as_ref().map(|t| t.0), Some(7));
+    assert_eq!(server.to_builder().finish().unwrap().port, None);
+}
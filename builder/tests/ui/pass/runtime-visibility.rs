@@ -0,0 +1,25 @@
+use builder::Builder;
+use builder_runtime::Buildable;
+
+// the builder's visibility may be spelled differently, as long as it reaches as far as the struct
+#[derive(Builder)]
+#[builder(runtime, vis = "pub(crate)")]
+pub(in crate) struct Server {
+    #[builder(default = 8080)]
+    port: u16,
+}
+
+// an enum is never Buildable, so its builders may be less visible
+#[derive(Builder)]
+#[builder(runtime, vis = "pub(crate)")]
+pub enum Event {
+    Click { x: i32 },
+}
+
+fn main() {
+    let server = builder_runtime::Builder::build(<Server as Buildable>::builder()).unwrap();
+    assert_eq!(server.port, 8080);
+
+    let event = builder_runtime::Builder::build(Event::click_builder().x(1)).unwrap();
+    assert!(matches!(event, Event::Click { x: 1 }));
+}
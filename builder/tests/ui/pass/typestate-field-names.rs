@@ -0,0 +1,15 @@
+use builder::Builder;
+
+// fields whose names only differ in underscores each get a state of their own
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Point {
+    x_1: u8,
+    x1: u8,
+}
+
+fn main() {
+    let point = Point::builder().x1(2u8).x_1(1u8).finish();
+
+    assert_eq!((point.x_1, point.x1), (1, 2));
+}
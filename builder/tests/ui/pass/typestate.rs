@@ -0,0 +1,23 @@
+use builder::Builder;
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Command {
+    executable: String,
+    current_dir: Option<String>,
+    port: u16,
+}
+
+fn main() {
+    // required fields may be set in any order, optional ones any number of times
+    let command = Command::builder()
+        .current_dir("/")
+        .port(8080u16)
+        .current_dir("/tmp")
+        .executable("find")
+        .finish();
+
+    assert_eq!(command.executable, "find");
+    assert_eq!(command.current_dir.as_deref(), Some("/tmp"));
+    assert_eq!(command.port, 8080);
+}